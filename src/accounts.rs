@@ -0,0 +1,93 @@
+use std::sync::{Arc, Mutex};
+use rand::random;
+use rusqlite::{Connection, params};
+use argon2::{self, Config};
+
+/// Shared handle to the server's `AccountStore`
+pub type AccountStoreReference = Arc<Mutex<AccountStore>>;
+
+/// A single leaderboard row: username, kills, peak size, survival time in seconds
+pub type LeaderboardRow = (String, u64, u64, u64);
+
+/// Persists accounts and per-player stats in SQLite. Passwords are hashed with Argon2
+/// (salted, PHC string format) before they ever touch the `accounts` table.
+pub struct AccountStore {
+    conn: Connection
+}
+
+impl AccountStore {
+    pub fn open(path: &str) -> AccountStoreReference {
+        let conn = Connection::open(path).expect("Unable to open account database");
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS accounts (
+                username TEXT PRIMARY KEY,
+                password_hash TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS scores (
+                username TEXT PRIMARY KEY REFERENCES accounts(username),
+                kills INTEGER NOT NULL DEFAULT 0,
+                peak_size INTEGER NOT NULL DEFAULT 0,
+                survival_secs INTEGER NOT NULL DEFAULT 0
+            );"
+        ).expect("Unable to initialize account schema");
+
+        Arc::new(Mutex::new(AccountStore { conn }))
+    }
+
+    /// Verifies `password` against the stored hash for `username`, registering a brand new
+    /// account on first login so there's no separate signup step. Note that this means `login`
+    /// doesn't actually gate new usernames -- it only rejects a *known* username with the
+    /// wrong password, or a real database error.
+    pub fn authenticate(&self, username: &str, password: &str) -> bool {
+        match self.conn.query_row(
+            "SELECT password_hash FROM accounts WHERE username = ?1",
+            params![username],
+            |row| row.get::<_, String>(0)
+        ) {
+            Ok(hash) => argon2::verify_encoded(&hash, password.as_bytes()).unwrap_or(false),
+            Err(rusqlite::Error::QueryReturnedNoRows) => self.create_account(username, password).is_ok(),
+            Err(_) => false
+        }
+    }
+
+    fn create_account(&self, username: &str, password: &str) -> Result<(), rusqlite::Error> {
+        let salt: [u8; 16] = random();
+        let hash = argon2::hash_encoded(password.as_bytes(), &salt, &Config::default())
+            .expect("Unable to hash password");
+
+        self.conn.execute(
+            "INSERT INTO accounts (username, password_hash) VALUES (?1, ?2)",
+            params![username, hash]
+        )?;
+        self.conn.execute(
+            "INSERT INTO scores (username) VALUES (?1)",
+            params![username]
+        )?;
+
+        Ok(())
+    }
+
+    /// Raises a player's best-ever stats; never lowers them
+    pub fn record_score(&self, username: &str, kills: u64, peak_size: u64, survival_secs: u64) {
+        self.conn.execute(
+            "UPDATE scores SET
+                kills = MAX(kills, ?2),
+                peak_size = MAX(peak_size, ?3),
+                survival_secs = MAX(survival_secs, ?4)
+             WHERE username = ?1",
+            params![username, kills, peak_size, survival_secs]
+        ).expect("Unable to record score");
+    }
+
+    pub fn leaderboard(&self, limit: u32) -> Vec<LeaderboardRow> {
+        let mut stmt = self.conn.prepare(
+            "SELECT username, kills, peak_size, survival_secs FROM scores ORDER BY peak_size DESC LIMIT ?1"
+        ).expect("Unable to prepare leaderboard query");
+
+        stmt.query_map(params![limit], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        }).expect("Unable to query leaderboard")
+            .filter_map(Result::ok)
+            .collect()
+    }
+}
@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use network::{ClientHandle, ClientId};
+
+/// Shared handle to a room's `ClientPool`
+pub type ClientPoolReference = Arc<Mutex<ClientPool>>;
+
+/// Owns every `ClientHandle` in a single room, keyed by `ClientId`. Apart from the join path,
+/// which seeds a reconnecting handle's watched entities right after inserting it, the mainloop
+/// is the only thing that reads from or writes to the handles it holds; sockets otherwise only
+/// reach them through the join/move/leave/chat channels.
+pub struct ClientPool {
+    next_id: ClientId,
+    handles: HashMap<ClientId, ClientHandle>
+}
+
+impl ClientPool {
+    pub fn new_shared() -> ClientPoolReference {
+        Arc::new(Mutex::new(ClientPool { next_id: 0, handles: HashMap::new() }))
+    }
+
+    /// Registers a handle and returns the ID the mainloop will use to refer to it
+    pub fn insert(&mut self, handle: ClientHandle) -> ClientId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.handles.insert(id, handle);
+        id
+    }
+
+    pub fn remove(&mut self, id: ClientId) {
+        self.handles.remove(&id);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    pub fn handles_mut(&mut self) -> impl Iterator<Item = &mut ClientHandle> {
+        self.handles.values_mut()
+    }
+
+    pub fn handles_mut_with_ids(&mut self) -> impl Iterator<Item = (&ClientId, &mut ClientHandle)> {
+        self.handles.iter_mut()
+    }
+
+    /// Raw access for operations, like chat fan-out, that need to scan every handle at once
+    pub fn handles_map(&mut self) -> &mut HashMap<ClientId, ClientHandle> {
+        &mut self.handles
+    }
+}
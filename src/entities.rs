@@ -0,0 +1,44 @@
+use rmpv::Value;
+use game_map::MapIndex;
+
+/// Type used to identify entities on the map
+pub type EntityId = u64;
+
+/// A single thing occupying space on a room's map (currently just players). Tracks enough to
+/// answer the questions `build_update_message` and the replay log ask of it: where it is,
+/// whether it has been marked as changed, and how to serialize it to clients.
+pub struct Entity {
+    index: MapIndex,
+    size: u64,
+    dirty: bool
+}
+
+impl Entity {
+    pub fn new(index: MapIndex, size: u64) -> Entity {
+        Entity { index, size, dirty: true }
+    }
+
+    pub fn index(&self) -> &MapIndex {
+        &self.index
+    }
+
+    /// Current size; doubles as the "peak size" stat until something tracks a running max
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Whether this entity is marked as changed. The mark is set when the entity is created and
+    /// whenever it moves, and nothing clears it, so this stays true once set.
+    pub fn needs_update(&self) -> bool {
+        self.dirty
+    }
+
+    pub(crate) fn set_index(&mut self, index: MapIndex) {
+        self.index = index;
+        self.dirty = true;
+    }
+
+    pub fn serialize(&self, id: &EntityId, _appeared: bool) -> Value {
+        Value::Array(vec![Value::from(*id), self.index.clone().into(), Value::from(self.size)])
+    }
+}
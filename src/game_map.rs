@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use rmpv::Value;
+use entities::{Entity, EntityId};
+
+/// Type used for map coordinates
+pub type IndexType = i64;
+
+/// Side length of a single spatial-grid bucket. Matches `ClientHandle::VIEW_RANGE` so a
+/// player's own cell plus its eight neighbors always covers everything within view range.
+pub(crate) const CELL_SIZE: IndexType = 15;
+
+/// A position on the map
+#[derive(Clone, Debug)]
+pub struct MapIndex {
+    pub x: IndexType,
+    pub y: IndexType
+}
+
+impl MapIndex {
+    pub fn new(x: IndexType, y: IndexType) -> MapIndex {
+        MapIndex { x, y }
+    }
+
+    /// The spatial-grid bucket this index falls into. Exposed beyond the grid itself so the
+    /// replay log can use the same notion of "significant move" as interest management does.
+    pub(crate) fn cell(&self) -> (IndexType, IndexType) {
+        (self.x / CELL_SIZE, self.y / CELL_SIZE)
+    }
+}
+
+impl From<MapIndex> for Value {
+    fn from(index: MapIndex) -> Value {
+        Value::Array(vec![Value::from(index.x), Value::from(index.y)])
+    }
+}
+
+/// Buckets every live entity by `CELL_SIZE` so `entities_near` only has to visit a handful of
+/// cells instead of scanning the whole map. Kept in sync incrementally as entities spawn,
+/// move, and die, rather than rebuilt from scratch every tick.
+#[derive(Default)]
+struct SpatialGrid {
+    buckets: HashMap<(IndexType, IndexType), Vec<EntityId>>
+}
+
+impl SpatialGrid {
+    fn insert(&mut self, id: EntityId, index: &MapIndex) {
+        self.buckets.entry(index.cell()).or_insert_with(Vec::new).push(id);
+    }
+
+    fn remove(&mut self, id: &EntityId, index: &MapIndex) {
+        if let Some(bucket) = self.buckets.get_mut(&index.cell()) {
+            bucket.retain(|existing| existing != id);
+        }
+    }
+
+    fn move_entity(&mut self, id: &EntityId, from: &MapIndex, to: &MapIndex) {
+        if from.cell() != to.cell() {
+            self.remove(id, from);
+            self.insert(id.clone(), to);
+        }
+    }
+
+    /// Every entity in `origin`'s cell and the cells within `range` of it. The visited cells
+    /// can reach past `range` on every side, so this can include entities outside it; callers
+    /// that care (`build_update_message`) re-check with `index_in_range` before trusting the result.
+    fn near(&self, origin: &MapIndex, range: IndexType) -> Vec<EntityId> {
+        let cell_radius = (range + CELL_SIZE - 1) / CELL_SIZE;
+        let (cx, cy) = origin.cell();
+
+        let mut ids = Vec::new();
+        for dx in -cell_radius..=cell_radius {
+            for dy in -cell_radius..=cell_radius {
+                if let Some(bucket) = self.buckets.get(&(cx + dx, cy + dy)) {
+                    ids.extend(bucket.iter().cloned());
+                }
+            }
+        }
+        ids
+    }
+}
+
+/// Holds every live and recently-destroyed entity in a single room, plus the spatial grid used
+/// for interest management.
+pub struct GameMap {
+    size: MapIndex,
+    entities: HashMap<EntityId, Entity>,
+    destroyed_entities: HashMap<EntityId, Entity>,
+    grid: SpatialGrid
+}
+
+impl GameMap {
+    pub fn new(size: MapIndex) -> GameMap {
+        GameMap {
+            size,
+            entities: HashMap::new(),
+            destroyed_entities: HashMap::new(),
+            grid: SpatialGrid::default()
+        }
+    }
+
+    pub fn map_size(&self) -> MapIndex {
+        self.size.clone()
+    }
+
+    pub fn entities(&self) -> &HashMap<EntityId, Entity> {
+        &self.entities
+    }
+
+    pub fn destroyed_entities(&self) -> &HashMap<EntityId, Entity> {
+        &self.destroyed_entities
+    }
+
+    pub fn entity_with_id(&self, id: &EntityId) -> Option<&Entity> {
+        self.entities.get(id)
+    }
+
+    pub fn spawn_entity(&mut self, id: EntityId, entity: Entity) {
+        self.grid.insert(id.clone(), entity.index());
+        self.entities.insert(id, entity);
+    }
+
+    pub fn move_entity(&mut self, id: &EntityId, to: MapIndex) {
+        if let Some(entity) = self.entities.get_mut(id) {
+            let from = entity.index().clone();
+            entity.set_index(to.clone());
+            self.grid.move_entity(id, &from, &to);
+        }
+    }
+
+    pub fn destroy_entity(&mut self, id: &EntityId) {
+        if let Some(entity) = self.entities.remove(id) {
+            self.grid.remove(id, entity.index());
+            self.destroyed_entities.insert(id.clone(), entity);
+        }
+    }
+
+    /// Entities near `origin`, found by visiting only the grid cells within `range` of it
+    /// instead of scanning every entity on the map.
+    pub fn entities_near(&self, origin: &MapIndex, range: IndexType) -> impl Iterator<Item = EntityId> {
+        self.grid.near(origin, range).into_iter()
+    }
+}
@@ -6,6 +6,9 @@ extern crate ws;
 extern crate rocket;
 extern crate rmpv;
 extern crate rand;
+extern crate rusqlite;
+extern crate argon2;
+extern crate prometheus;
 #[macro_use] extern crate lazy_static;
 #[macro_use] extern crate maplit;
 
@@ -15,11 +18,19 @@ mod network;
 mod entities;
 mod game_map;
 mod game;
+mod rooms;
+mod accounts;
+mod metrics;
+mod clientpool;
+mod mainloop;
+mod replay;
 
 use std::thread;
 use std::path::{Path, PathBuf};
 use ws::listen;
 use rocket::response::{NamedFile};
+use rocket::State;
+use accounts::AccountStoreReference;
 
 #[get("/")]
 fn index() -> Option<NamedFile> {
@@ -31,23 +42,43 @@ fn files(file: PathBuf) -> Option<NamedFile> {
     NamedFile::open(Path::new("public/").join(file)).ok()
 }
 
+#[get("/leaderboard")]
+fn leaderboard(accounts: State<AccountStoreReference>) -> String {
+    accounts.lock().unwrap().leaderboard(50).into_iter()
+        .map(|(username, kills, peak_size, survival_secs)|
+            format!("{}\t{}\t{}\t{}", username, kills, peak_size, survival_secs))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[get("/metrics")]
+fn metrics_endpoint() -> String {
+    metrics::METRICS.render()
+}
+
 fn main() {
-    // Start the game
-    let game = game::Game::new();
+    // Open the persistent account + leaderboard store
+    let accounts = accounts::AccountStore::open("scrunch.db");
+
+    // Start the room registry; rooms are created on demand as clients join them, and record
+    // their players' stats to the account store when they leave
+    let registry = rooms::RoomRegistry::new(accounts.clone());
 
     // Start the websocket server
     {
-        let game = game.clone();
+        let registry = registry.clone();
+        let accounts = accounts.clone();
         thread::spawn(move || {
             listen(
                 "0.0.0.0:8080",
-                |out| network::Client::new(game.clone(), out)
+                |out| network::Client::new(registry.clone(), accounts.clone(), out)
             ).unwrap();
         });
     }
 
     // Start the rocket server
     rocket::ignite()
-        .mount("/", routes![index, files])
+        .mount("/", routes![index, files, leaderboard, metrics_endpoint])
+        .manage(accounts)
         .launch();
 }
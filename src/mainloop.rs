@@ -0,0 +1,108 @@
+use std::thread;
+use std::time::{Duration, Instant};
+use game::GameReference;
+use clientpool::ClientPoolReference;
+use replay::ReplayBufferReference;
+use rooms::{RoomId, RoomRegistryReference};
+use accounts::AccountStoreReference;
+use network::broadcast_chat;
+
+/// Ticks per second the mainloop advances a room at
+const TICK_RATE: u64 = 20;
+
+/// Spawns the fixed-tick loop that drives a single room on a dedicated thread.
+pub fn spawn(room_id: RoomId, registry: RoomRegistryReference, game: GameReference, pool: ClientPoolReference, replay: ReplayBufferReference, accounts: AccountStoreReference) {
+    thread::spawn(move || run(room_id, registry, game, pool, replay, accounts));
+}
+
+/// The authoritative per-room loop: drain every handle's inbound channels, advance the
+/// `GameMap` once, then build and flush an update frame for every handle. This replaces the
+/// old arrangement where each socket's own thread mutated shared state as messages arrived;
+/// here a single thread per room makes tick rate, input ordering, and backpressure explicit.
+///
+/// Runs until its pool empties out. Rather than quit the moment a tick observes an empty
+/// pool -- a client could join the instant after -- it asks the registry to reap the room and
+/// only stops once the registry confirms the room really is gone; otherwise it keeps ticking.
+fn run(room_id: RoomId, registry: RoomRegistryReference, game: GameReference, pool: ClientPoolReference, replay: ReplayBufferReference, accounts: AccountStoreReference) {
+    let tick_duration = Duration::from_millis(1000 / TICK_RATE);
+
+    loop {
+        let tick_start = Instant::now();
+
+        tick(&game, &pool, &replay, &accounts);
+
+        if pool.lock().unwrap().is_empty() && registry.lock().unwrap().reap(&room_id, &pool) {
+            break;
+        }
+
+        if let Some(remaining) = tick_duration.checked_sub(tick_start.elapsed()) {
+            thread::sleep(remaining);
+        }
+    }
+}
+
+fn tick(game: &GameReference, pool: &ClientPoolReference, replay: &ReplayBufferReference, accounts: &AccountStoreReference) {
+    let mut pool = pool.lock().unwrap();
+    let mut game = game.lock().unwrap();
+
+    // Drain every handle's inbound channels before the world advances; chat lines and
+    // departures are collected instead of acted on immediately, since fanning out a chat
+    // line and dropping a handle both need to look across the whole pool at once
+    let mut chat_lines = Vec::new();
+    let mut ids_to_remove = Vec::new();
+
+    for (id, handle) in pool.handles_mut_with_ids() {
+        handle.validate_player_ids(&game);
+
+        while let Ok(username) = handle.rx_join.try_recv() {
+            game.spawn_player(handle, username);
+        }
+        while let Ok(index) = handle.rx_move.try_recv() {
+            game.move_player(handle, index);
+        }
+        while let Ok(text) = handle.rx_chat.try_recv() {
+            if let Some(sender_id) = handle.player_id {
+                chat_lines.push((sender_id, handle.spectating_index.clone(), text));
+            }
+        }
+        if handle.rx_leave.try_recv().is_ok() {
+            // Persist this player's best-ever stats before the entity it was spectating
+            // disappears out from under us. Only for handles that logged in: an anonymous
+            // join_game can claim any username, so trusting it here would let a guest
+            // overwrite a real account's leaderboard row.
+            if handle.authenticated {
+                if let Some(ref username) = handle.username {
+                    let peak_size = handle.player_id
+                        .and_then(|player_id| game.entities().get(&player_id))
+                        .map(|entity| entity.size())
+                        .unwrap_or(0);
+                    let survival_secs = handle.joined_at.elapsed().as_secs();
+                    accounts.lock().unwrap().record_score(username, handle.kills, peak_size, survival_secs);
+                }
+            }
+
+            game.remove_player(handle);
+            ids_to_remove.push(*id);
+        }
+    }
+
+    for id in ids_to_remove {
+        pool.remove(id);
+    }
+
+    // Advance the world once per tick
+    game.step();
+
+    // Record what changed so a reconnecting client can be caught up later
+    replay.lock().unwrap().record_tick(&game);
+
+    // Fan each chat line out to the handles spectating near where it was spoken
+    for (sender_id, spectating_index, text) in chat_lines {
+        broadcast_chat(pool.handles_map(), &spectating_index, sender_id, text);
+    }
+
+    // Flush an update frame to every handle
+    for handle in pool.handles_mut() {
+        handle.build_update_message(&game);
+    }
+}
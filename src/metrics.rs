@@ -0,0 +1,70 @@
+use prometheus::{Registry, IntCounter, IntCounterVec, IntGauge, Histogram, HistogramOpts, Opts, Encoder, TextEncoder};
+
+lazy_static! {
+    /// Process-wide metrics registry, gathered by the `/metrics` route
+    pub static ref METRICS: Metrics = Metrics::new();
+}
+
+/// Operational metrics for the WebSocket/game server, exposed over `/metrics` in the
+/// Prometheus text exposition format.
+pub struct Metrics {
+    registry: Registry,
+
+    pub open_connections: IntGauge,
+    pub messages_parsed_total: IntCounter,
+    pub messages_by_type: IntCounterVec,
+    pub bytes_sent: Histogram,
+    pub entities_live: IntGauge,
+    pub entities_destroyed: IntGauge
+}
+
+impl Metrics {
+    fn new() -> Metrics {
+        let registry = Registry::new();
+
+        let open_connections = IntGauge::new(
+            "scrunch_open_connections", "Number of open WebSocket connections"
+        ).unwrap();
+        registry.register(Box::new(open_connections.clone())).unwrap();
+
+        let messages_parsed_total = IntCounter::new(
+            "scrunch_messages_parsed_total", "Total inbound messages parsed"
+        ).unwrap();
+        registry.register(Box::new(messages_parsed_total.clone())).unwrap();
+
+        let messages_by_type = IntCounterVec::new(
+            Opts::new("scrunch_messages_by_type_total", "Inbound messages parsed, keyed by message type"),
+            &["message_type"]
+        ).unwrap();
+        registry.register(Box::new(messages_by_type.clone())).unwrap();
+
+        let bytes_sent = Histogram::with_opts(
+            HistogramOpts::new("scrunch_bytes_sent", "Bytes written per outbound message")
+        ).unwrap();
+        registry.register(Box::new(bytes_sent.clone())).unwrap();
+
+        let entities_live = IntGauge::new(
+            "scrunch_entities_live", "Live entity count, sampled each time an update message is built"
+        ).unwrap();
+        registry.register(Box::new(entities_live.clone())).unwrap();
+
+        let entities_destroyed = IntGauge::new(
+            "scrunch_entities_destroyed", "Destroyed entity count, sampled each time an update message is built"
+        ).unwrap();
+        registry.register(Box::new(entities_destroyed.clone())).unwrap();
+
+        Metrics {
+            registry,
+            open_connections, messages_parsed_total, messages_by_type,
+            bytes_sent, entities_live, entities_destroyed
+        }
+    }
+
+    /// Encodes every registered metric using the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let metric_families = self.registry.gather();
+        let mut buf = Vec::new();
+        TextEncoder::new().encode(&metric_families, &mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+}
@@ -1,14 +1,18 @@
 use ws::{Handler, Sender as WsSender, Result as WsResult, Message, CloseCode, Handshake, Error};
-use std::collections::HashSet;
+use std::collections::{HashSet, HashMap};
 use std::sync::mpsc::{channel, Sender, Receiver};
+use std::time::Instant;
 use std::io::Cursor;
 use std::fmt::{Display, Formatter, Result as FormatResult};
 use rmpv::Value;
 use rmpv::encode::write_value;
 use rmpv::decode::read_value;
-use game::{GameReference};
 use game_map::{GameMap, MapIndex, IndexType};
 use entities::{EntityId};
+use rooms::{RoomId, RoomRegistryReference, DEFAULT_ROOM};
+use accounts::AccountStoreReference;
+use metrics::METRICS;
+use replay::Sequence;
 
 /// Errors that occur when processing messages
 #[derive(Debug)]
@@ -33,12 +37,23 @@ pub struct ClientHandle {
 
     watching_entities: HashSet<EntityId>,
 
-    spectating_index: MapIndex, /// Index at which the player is spectating from; this is used for if the player dies and doesn't have a position
+    pub(crate) spectating_index: MapIndex, /// Index at which the player is spectating from; this is used for if the player dies and doesn't have a position
     pub player_id: Option<EntityId>,
 
+    /// Account this handle is playing as, if it joined with a username; used to attribute
+    /// leaderboard stats to the right account when it leaves
+    pub(crate) username: Option<String>,
+    /// Whether `username` was verified via `login`, as opposed to claimed by an anonymous
+    /// `join_game`; only an authenticated handle's stats are persisted, so a guest can't pick
+    /// someone else's username and overwrite their leaderboard row
+    pub(crate) authenticated: bool,
+    pub(crate) joined_at: Instant,
+    pub(crate) kills: u64,
+
     pub rx_join: Receiver<String>,
     pub rx_move: Receiver<MapIndex>,
-    pub rx_leave: Receiver<()>
+    pub rx_leave: Receiver<()>,
+    pub rx_chat: Receiver<String>
 }
 
 impl SocketSender for ClientHandle {
@@ -51,6 +66,12 @@ impl ClientHandle {
     /// Distance from the player that can be seen
     const VIEW_RANGE: IndexType = 15;
 
+    /// Credits this handle's player with a kill, for the leaderboard stats recorded when it
+    /// eventually leaves
+    pub fn record_kill(&mut self) {
+        self.kills += 1;
+    }
+
     pub fn validate_player_ids(&mut self, map: &GameMap) {
         if let Some(id) = self.player_id {
             if !map.entities().contains_key(&id) {
@@ -67,6 +88,10 @@ impl ClientHandle {
     }
 
     pub fn build_update_message(&mut self, map: &GameMap) {
+        // Sample entity counts for observability
+        METRICS.entities_live.set(map.entities().len() as i64);
+        METRICS.entities_destroyed.set(map.destroyed_entities().len() as i64);
+
         /* Determine items */
         // Create lists to hold the data
         let mut appeared_entities = Vec::<EntityId>::new();
@@ -100,10 +125,14 @@ impl ClientHandle {
             }
         }
 
-        // Find new entities to start watching
-        for (id, object) in map.entities().iter() {
-            if !self.watching_entities.contains(id) && self.index_in_range(&self.spectating_index, object.index()) {
-                appeared_entities.push(id.clone());
+        // Find new entities to start watching; `entities_near` only visits the grid cells
+        // around `spectating_index` instead of every entity on the map, and `index_in_range`
+        // trims the handful of false positives the grid's square cells can include
+        for id in map.entities_near(&self.spectating_index, ClientHandle::VIEW_RANGE) {
+            if let Some(object) = map.entity_with_id(&id) {
+                if !self.watching_entities.contains(&id) && self.index_in_range(&self.spectating_index, object.index()) {
+                    appeared_entities.push(id.clone());
+                }
             }
         }
 
@@ -141,6 +170,25 @@ impl ClientHandle {
             self.watching_entities.remove(&id);
         }
     }
+
+    /// Sends a chat line to this client, attributed to `sender_id`
+    fn send_chat(&self, sender_id: EntityId, text: &str) {
+        self.send_message(MessageType::Chat, Value::Array(vec![
+            Value::from(sender_id),
+            Value::from(text)
+        ]));
+    }
+}
+
+/// Fans a chat line spoken from `spectating_index` out to every handle within
+/// `ClientHandle::VIEW_RANGE` of it, mirroring the way `build_update_message` scopes entity
+/// visibility to nearby players.
+pub fn broadcast_chat(handles: &mut HashMap<ClientId, ClientHandle>, spectating_index: &MapIndex, sender_id: EntityId, text: String) {
+    for handle in handles.values() {
+        if handle.index_in_range(spectating_index, &handle.spectating_index) {
+            handle.send_chat(sender_id, &text);
+        }
+    }
 }
 
 /// Handles incoming connections; one is created for every individual WebSocket connection created.
@@ -148,47 +196,38 @@ impl ClientHandle {
 pub struct Client {
     out: WsSender,
     is_open: bool,
+    registry: RoomRegistryReference,
+    accounts: AccountStoreReference,
 
-    tx_join: Sender<String>,
-    tx_move: Sender<MapIndex>,
-    tx_leave: Sender<()>
+    // Only populated once `join_game`/`login` has placed this client in a room
+    tx_move: Option<Sender<MapIndex>>,
+    tx_leave: Option<Sender<()>>,
+    tx_chat: Option<Sender<String>>
 }
 
 impl Client {
-    pub fn new(game: GameReference, out: WsSender) -> Client {
-        // Create the channels
-        let (tx_join, rx_join) = channel();
-        let (tx_move, rx_move) = channel();
-        let (tx_leave, rx_leave) = channel();
-
-        // Register the connection with the game
-        {
-            let mut game = game.lock().unwrap();
-            game.add_client(ClientHandle {
-                out: out.clone(),
-                watching_entities: HashSet::new(),
-                spectating_index: MapIndex::new(0,0),
-                player_id: None,
-                rx_join, rx_move, rx_leave
-            });
-        }
-
-        // Return the server
+    pub fn new(registry: RoomRegistryReference, accounts: AccountStoreReference, out: WsSender) -> Client {
         Client {
-            out, is_open: false,
-            tx_join, tx_move, tx_leave
+            out, is_open: false, registry, accounts,
+            tx_move: None, tx_leave: None, tx_chat: None
         }
     }
 
     fn close(&mut self) {
-        // Send leave message
-        self.tx_leave.send(()).unwrap();
+        // Send leave message, if we ever joined a room. The handle isn't actually removed from
+        // the pool until the room's mainloop picks this up on its next tick, and that same tick
+        // is what reaps the room if it ends up empty -- reaping from here would race it and
+        // never catch a room that's already past its last tick.
+        if let Some(ref tx_leave) = self.tx_leave {
+            tx_leave.send(()).unwrap();
+        }
 
         // Close the socket
         self.out.close(CloseCode::Empty).unwrap();
 
         // Set to not open
         self.is_open = false;
+        METRICS.open_connections.dec();
     }
 }
 
@@ -198,6 +237,7 @@ impl Handler for Client {
 
         // Set to open
         self.is_open = true;
+        METRICS.open_connections.inc();
 
         Ok(())
     }
@@ -239,7 +279,7 @@ impl SocketSender for Client {
 
 /* Message handlers */
 impl Client {
-    fn parse_message(&self, msg: Message) -> Result<(), MessageError> {
+    fn parse_message(&mut self, msg: Message) -> Result<(), MessageError> {
         // Get the data from the message
         let message_value = match msg {
             Message::Binary(data) => {
@@ -258,25 +298,158 @@ impl Client {
         let message_type = unwrap_data!(message[0].as_u64());
         let message_body = &message[1];
 
+        // Track what came in before dispatching it. The label is limited to the known message
+        // types (plus a single "unknown" bucket) rather than the raw client-controlled integer,
+        // so a peer sending random type values can't grow the label's cardinality without bound.
+        METRICS.messages_parsed_total.inc();
+        let message_type_label = match message_type {
+            0 => "join_game",
+            1 => "move_player",
+            2 => "chat_message",
+            3 => "list_rooms",
+            4 => "login",
+            5 => "request_leaderboard",
+            _ => "unknown"
+        };
+        METRICS.messages_by_type.with_label_values(&[message_type_label]).inc();
+
         // Handle the message
         match message_type {
             0 => self.join_game(message_body),
             1 => self.move_player(message_body),
+            2 => self.chat_message(message_body),
+            3 => self.list_rooms(),
+            4 => self.login(message_body),
+            5 => self.request_leaderboard(),
             _ => Err(MessageError::EventType)
         }
     }
 
-    fn join_game(&self, data: &Value) -> Result<(), MessageError> { // TODO: Use `data`
-        // Get the username
-        let username: String = unwrap_data!(data.as_str()).to_string();
+    fn join_game(&mut self, data: &Value) -> Result<(), MessageError> {
+        // Get the username, optionally the room to join, and optionally the last event
+        // sequence number this client (if it's reconnecting) already saw
+        let parts: &Vec<Value> = unwrap_data!(data.as_array());
+        if parts.is_empty() || parts.len() > 3 {
+            return Err(MessageError::MissingData)
+        }
+        let username: String = unwrap_data!(parts[0].as_str()).to_string();
+        let room_id: RoomId = match parts.get(1).and_then(|room| room.as_str()) {
+            Some(room) => room.to_string(),
+            None => DEFAULT_ROOM.to_string()
+        };
+        let last_sequence: Option<Sequence> = parts.get(2).and_then(|sequence| sequence.as_u64());
+
+        // No credentials were sent; play as an anonymous guest
+        self.spawn_in_room(username, false, room_id, last_sequence)
+    }
+
+    fn login(&mut self, data: &Value) -> Result<(), MessageError> {
+        // Get the username, password, and optionally the last event sequence number seen
+        let parts: &Vec<Value> = unwrap_data!(data.as_array());
+        if parts.len() < 2 || parts.len() > 3 {
+            return Err(MessageError::MissingData)
+        }
+        let username: String = unwrap_data!(parts[0].as_str()).to_string();
+        let password: String = unwrap_data!(parts[1].as_str()).to_string();
+        let last_sequence: Option<Sequence> = parts.get(2).and_then(|sequence| sequence.as_u64());
 
-        // Send the message
-        self.tx_join.send(username).unwrap();
+        // Verify the credentials before letting the player spawn
+        if !self.accounts.lock().unwrap().authenticate(&username, &password) {
+            return Err(MessageError::MissingData)
+        }
+
+        self.spawn_in_room(username, true, DEFAULT_ROOM.to_string(), last_sequence)
+    }
+
+    /// Registers a new `ClientHandle` in `room_id`'s `ClientPool` and wires up this client's
+    /// move/leave/chat channels to it; shared by the anonymous and authenticated join paths.
+    /// The room's mainloop -- not this socket's thread -- is what drains those channels from here on.
+    /// If `last_sequence` is given, the client is caught up with everything the room's replay
+    /// log recorded since then instead of starting from a cold `build_update_message`.
+    fn spawn_in_room(&mut self, username: String, authenticated: bool, room_id: RoomId, last_sequence: Option<Sequence>) -> Result<(), MessageError> {
+        // Create the channels
+        let (tx_join, rx_join) = channel();
+        let (tx_move, rx_move) = channel();
+        let (tx_leave, rx_leave) = channel();
+        let (tx_chat, rx_chat) = channel();
+
+        // Register the connection with the chosen room's pool
+        let registry = self.registry.clone();
+        let (_, pool, replay, client_id) = registry.lock().unwrap().join(&registry, &room_id, ClientHandle {
+            out: self.out.clone(),
+            watching_entities: HashSet::new(),
+            spectating_index: MapIndex::new(0,0),
+            player_id: None,
+            username: Some(username.clone()),
+            authenticated,
+            joined_at: Instant::now(),
+            kills: 0,
+            rx_join, rx_move, rx_leave, rx_chat
+        });
+
+        // Send the join message now that the handle is registered; the mainloop picks it up on its next tick
+        tx_join.send(username).unwrap();
+
+        // Replay anything the client missed, if it told us where it left off
+        if let Some(last_sequence) = last_sequence {
+            let events: Vec<Value> = {
+                let replay = replay.lock().unwrap();
+                replay.since(last_sequence).into_iter().map(|event| event.body.clone()).collect()
+            };
+
+            // The replay already told this client about every entity these events mention, so
+            // seed watching_entities with them; otherwise the very next build_update_message
+            // would re-report them all as freshly "appeared", making the replay purely
+            // additive instead of actually replacing the cold rebuild
+            let mut known_entities = HashSet::new();
+            for event in &events {
+                if let Some(parts) = event.as_array() {
+                    let id = parts.get(1).and_then(|id| id.as_u64());
+                    match (parts.get(0).and_then(|tag| tag.as_str()), id) {
+                        (Some("death"), Some(id)) => { known_entities.remove(&id); }
+                        (Some(_), Some(id)) => { known_entities.insert(id); }
+                        _ => {}
+                    }
+                }
+            }
+            if let Some(handle) = pool.lock().unwrap().handles_map().get_mut(&client_id) {
+                handle.watching_entities = known_entities;
+            }
+
+            let replay = replay.lock().unwrap();
+            self.send_message(MessageType::Replay, Value::Array(vec![
+                Value::Array(events),
+                Value::from(replay.current_sequence())
+            ]));
+        }
+
+        // Remember the channels so later messages reach this room
+        self.tx_move = Some(tx_move);
+        self.tx_leave = Some(tx_leave);
+        self.tx_chat = Some(tx_chat);
+
+        Ok(())
+    }
+
+    fn request_leaderboard(&self) -> Result<(), MessageError> {
+        let rows = self.accounts.lock().unwrap().leaderboard(50);
+        let message = Value::Array(rows.into_iter()
+            .map(|(username, kills, peak_size, survival_secs)| Value::Array(vec![
+                Value::from(username),
+                Value::from(kills),
+                Value::from(peak_size),
+                Value::from(survival_secs)
+            ]))
+            .collect());
+
+        self.send_message(MessageType::Leaderboard, message);
 
         Ok(())
     }
 
     fn move_player(&self, data: &Value) -> Result<(), MessageError> {
+        let tx_move = self.tx_move.as_ref().ok_or(MessageError::MissingData)?;
+
         // Parse the data
         let index_raw: &Vec<Value> = unwrap_data!(data.as_array());
         if index_raw.len() != 2 {
@@ -291,7 +464,31 @@ impl Client {
         };
 
         // Send the message
-        self.tx_move.send(index).unwrap();
+        tx_move.send(index).unwrap();
+
+        Ok(())
+    }
+
+    fn chat_message(&self, data: &Value) -> Result<(), MessageError> {
+        let tx_chat = self.tx_chat.as_ref().ok_or(MessageError::MissingData)?;
+
+        // Get the chat text
+        let text: String = unwrap_data!(data.as_str()).to_string();
+
+        // Send the message
+        tx_chat.send(text).unwrap();
+
+        Ok(())
+    }
+
+    fn list_rooms(&self) -> Result<(), MessageError> {
+        // Gather the active rooms and their player counts
+        let rooms = self.registry.lock().unwrap().room_counts();
+        let message = Value::Array(rooms.into_iter()
+            .map(|(room_id, count)| Value::Array(vec![Value::from(room_id), Value::from(count as u64)]))
+            .collect());
+
+        self.send_message(MessageType::RoomList, message);
 
         Ok(())
     }
@@ -301,14 +498,22 @@ impl Client {
 /// Declares the type of message
 pub enum MessageType {
     Join,
-    Update
+    Update,
+    Chat,
+    RoomList,
+    Leaderboard,
+    Replay
 }
 
 impl MessageType {
     pub fn message_flag(&self) -> u8 {
         match self {
             &MessageType::Join => 0,
-            &MessageType::Update => 1
+            &MessageType::Update => 1,
+            &MessageType::Chat => 2,
+            &MessageType::RoomList => 3,
+            &MessageType::Leaderboard => 4,
+            &MessageType::Replay => 5
         }
     }
 }
@@ -324,6 +529,7 @@ pub trait SocketSender {
         // Serialize the message
         let mut buf = Vec::new();
         write_value(&mut buf, &message).unwrap();
+        METRICS.bytes_sent.observe(buf.len() as f64);
 
         // Send the message
         self.socket_out().send(buf).unwrap();
@@ -0,0 +1,111 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use rmpv::Value;
+use game_map::{GameMap, MapIndex};
+use entities::EntityId;
+
+/// A monotonically increasing event sequence number, scoped to a single room
+pub type Sequence = u64;
+
+/// How many recent events a room retains for reconnecting clients. Sized assuming moves are
+/// only recorded on a grid-cell crossing (see `record_tick`) rather than every changed tick, so
+/// this comfortably covers a client reconnecting after a several-second drop even with a full
+/// room of entities drifting between cells.
+pub const DEFAULT_CAPACITY: usize = 4096;
+
+/// Shared handle to a room's `ReplayBuffer`
+pub type ReplayBufferReference = Arc<Mutex<ReplayBuffer>>;
+
+/// A single recorded world event, ready to be replayed to a reconnecting client
+pub struct SequencedEvent {
+    pub sequence: Sequence,
+    pub body: Value
+}
+
+/// A bounded, per-room ring buffer of recent spawns, deaths, and moves, each tagged with a
+/// sequence number. Lets a returning client ask for everything it missed since the last
+/// sequence it saw, instead of rebuilding its view of the world from a cold start.
+pub struct ReplayBuffer {
+    capacity: usize,
+    next_sequence: Sequence,
+    events: VecDeque<SequencedEvent>,
+    last_positions: HashMap<EntityId, MapIndex>
+}
+
+impl ReplayBuffer {
+    pub fn new_shared(capacity: usize) -> ReplayBufferReference {
+        Arc::new(Mutex::new(ReplayBuffer {
+            capacity,
+            next_sequence: 0,
+            events: VecDeque::with_capacity(capacity),
+            last_positions: HashMap::new()
+        }))
+    }
+
+    fn push(&mut self, body: Value) {
+        self.events.push_back(SequencedEvent { sequence: self.next_sequence, body });
+        self.next_sequence += 1;
+
+        if self.events.len() > self.capacity {
+            self.events.pop_front();
+        }
+    }
+
+    /// The sequence number that will be assigned to the next recorded event
+    pub fn current_sequence(&self) -> Sequence {
+        self.next_sequence
+    }
+
+    /// The oldest sequence number still retained in the buffer
+    fn oldest_sequence(&self) -> Sequence {
+        self.events.front().map(|event| event.sequence).unwrap_or(self.next_sequence)
+    }
+
+    /// Every retained event after `since`, clamped to the oldest one still in the buffer
+    pub fn since(&self, since: Sequence) -> Vec<&SequencedEvent> {
+        let floor = self.oldest_sequence().saturating_sub(1);
+        let since = since.max(floor);
+        self.events.iter().filter(|event| event.sequence > since).collect()
+    }
+
+    /// Diffs `map` against the previous tick's snapshot, recording a spawn for every entity
+    /// seen for the first time, a death for every entity that's no longer on the map, and a
+    /// move for every entity that crossed into a different grid cell. Sub-cell jitter within a
+    /// tick isn't "significant" enough to spend a slot in the buffer on -- at 20 Hz, recording
+    /// every changed position would exhaust `DEFAULT_CAPACITY` in well under a second under any
+    /// real load, leaving a reconnecting client with almost nothing to catch up on.
+    pub fn record_tick(&mut self, map: &GameMap) {
+        let mut seen = HashSet::new();
+        let mut recorded = Vec::new();
+
+        for (id, entity) in map.entities().iter() {
+            seen.insert(id.clone());
+            let index = entity.index().clone();
+
+            match self.last_positions.get(id) {
+                Some(previous) if previous.cell() == index.cell() => {} // Not a significant move
+                Some(_) => recorded.push(Value::Array(vec![
+                    Value::from("move"), Value::from(id.clone()), index.clone().into()
+                ])),
+                None => recorded.push(Value::Array(vec![
+                    Value::from("spawn"), Value::from(id.clone()), index.clone().into()
+                ]))
+            }
+
+            self.last_positions.insert(id.clone(), index);
+        }
+
+        let departed: Vec<EntityId> = self.last_positions.keys()
+            .filter(|id| !seen.contains(*id))
+            .cloned()
+            .collect();
+        for id in departed {
+            self.last_positions.remove(&id);
+            recorded.push(Value::Array(vec![Value::from("death"), Value::from(id)]));
+        }
+
+        for event in recorded {
+            self.push(event);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use game::{Game, GameReference};
+use clientpool::{ClientPool, ClientPoolReference};
+use replay::{ReplayBuffer, ReplayBufferReference, DEFAULT_CAPACITY};
+use accounts::AccountStoreReference;
+use network::{ClientHandle, ClientId};
+use mainloop;
+
+/// Identifies a single game room by name
+pub type RoomId = String;
+
+/// Room a client joins when it doesn't request one by name
+pub const DEFAULT_ROOM: &'static str = "default";
+
+/// Shared handle to the server's `RoomRegistry`
+pub type RoomRegistryReference = Arc<Mutex<RoomRegistry>>;
+
+/// A room's game state, the client pool its mainloop drives, and its replay log
+struct Room {
+    game: GameReference,
+    pool: ClientPoolReference,
+    replay: ReplayBufferReference
+}
+
+/// Tracks every room the server is currently hosting, keyed by room name. Rooms are created
+/// the first time a client asks to join them, given their own mainloop thread, and reaped
+/// once their last client leaves.
+pub struct RoomRegistry {
+    rooms: HashMap<RoomId, Room>,
+    accounts: AccountStoreReference
+}
+
+impl RoomRegistry {
+    pub fn new(accounts: AccountStoreReference) -> RoomRegistryReference {
+        Arc::new(Mutex::new(RoomRegistry { rooms: HashMap::new(), accounts }))
+    }
+
+    /// Registers `handle` in `room_id`'s pool, spinning up a fresh room (and its mainloop) if
+    /// it doesn't exist yet. The handle is inserted into the pool *before* a new room's
+    /// mainloop thread is spawned, so that thread never observes an empty pool before its
+    /// first client has joined. Also returns the `ClientId` the handle was registered under,
+    /// so the caller can still reach it (e.g. to seed its replay state) after it's inside the
+    /// pool the mainloop now owns.
+    pub fn join(&mut self, registry: &RoomRegistryReference, room_id: &str, handle: ClientHandle) -> (GameReference, ClientPoolReference, ReplayBufferReference, ClientId) {
+        let is_new = !self.rooms.contains_key(room_id);
+        let room = self.rooms.entry(room_id.to_string()).or_insert_with(|| Room {
+            game: Game::new(),
+            pool: ClientPool::new_shared(),
+            replay: ReplayBuffer::new_shared(DEFAULT_CAPACITY)
+        });
+        let client_id = room.pool.lock().unwrap().insert(handle);
+
+        if is_new {
+            mainloop::spawn(room_id.to_string(), registry.clone(), room.game.clone(), room.pool.clone(), room.replay.clone(), self.accounts.clone());
+        }
+
+        (room.game.clone(), room.pool.clone(), room.replay.clone(), client_id)
+    }
+
+    /// Removes `room_id` if its pool is still the one the mainloop found empty and is still
+    /// empty under this lock. Called from the room's own mainloop once its pool empties, not
+    /// from a socket thread, so it can't race a client joining the instant before the room
+    /// would otherwise be reaped. Returns whether the room was actually removed: if a new
+    /// client slipped in between the mainloop's check and this call, the room is kept and its
+    /// mainloop should keep running.
+    pub fn reap(&mut self, room_id: &RoomId, pool: &ClientPoolReference) -> bool {
+        let still_empty = self.rooms.get(room_id)
+            .map(|room| Arc::ptr_eq(&room.pool, pool) && room.pool.lock().unwrap().is_empty())
+            .unwrap_or(false);
+
+        if still_empty {
+            self.rooms.remove(room_id);
+        }
+        still_empty
+    }
+
+    /// Lists each active room alongside its current player count
+    pub fn room_counts(&self) -> Vec<(RoomId, usize)> {
+        self.rooms.iter()
+            .map(|(id, room)| (id.clone(), room.pool.lock().unwrap().len()))
+            .collect()
+    }
+}